@@ -0,0 +1,341 @@
+// Compiles a regex pattern string into an `NFAEngine` using Thompson's
+// construction. The pattern is first parsed into an `Ast` using the
+// combinators from the `parser` module, then each node is turned into an
+// NFA "fragment" - a `(start_state, accept_state)` pair - that gets wired
+// into a shared engine.
+
+use crate::dfa::{Matchers, NFAEngine, PredicateFn};
+use crate::parser::{char_parser, class_parser, ParseResult, Parser};
+
+#[derive(Clone)]
+enum Ast {
+    Literal(char),
+    Class(PredicateFn),
+    // `^` or `$`, already compiled to the zero-width matcher that enforces it.
+    Anchor(Matchers),
+    // A capturing group, tagged with its 1-based group index in open-paren order.
+    Group(usize, Box<Ast>),
+    Concat(Vec<Ast>),
+    Alternation(Box<Ast>, Box<Ast>),
+    Star(Box<Ast>),
+    Plus(Box<Ast>),
+    Optional(Box<Ast>),
+}
+
+fn is_plain_char(c: &char) -> bool {
+    !matches!(c, '(' | ')' | '|' | '*' | '+' | '?' | '^' | '$')
+}
+
+fn literal(input: &str) -> ParseResult<'_, Ast> {
+    char_parser().filter(is_plain_char).map(Ast::Literal)(input)
+}
+
+// `groups` counts capturing groups in open-paren order, so the group index assigned here
+// matches the usual left-to-right numbering convention.
+fn atom<'a>(input: &'a str, groups: &mut usize) -> ParseResult<'a, Ast> {
+    if let Some(rest) = input.strip_prefix('^') {
+        return Ok((rest, Ast::Anchor(Matchers::new_start_anchor())));
+    }
+    if let Some(rest) = input.strip_prefix('$') {
+        return Ok((rest, Ast::Anchor(Matchers::new_end_anchor())));
+    }
+    if let Some(rest) = input.strip_prefix('(') {
+        *groups += 1;
+        let id = *groups;
+        let (rest, inner) = alternation(rest, groups)?;
+        return match rest.strip_prefix(')') {
+            Some(rest) => Ok((rest, Ast::Group(id, Box::new(inner)))),
+            None => Err(input),
+        };
+    }
+    if input.starts_with('[') {
+        return class_parser().map(Ast::Class)(input);
+    }
+    literal(input)
+}
+
+fn repeat<'a>(input: &'a str, groups: &mut usize) -> ParseResult<'a, Ast> {
+    let (rest, node) = atom(input, groups)?;
+    match rest.chars().next() {
+        Some('*') => Ok((&rest[1..], Ast::Star(Box::new(node)))),
+        Some('+') => Ok((&rest[1..], Ast::Plus(Box::new(node)))),
+        Some('?') => Ok((&rest[1..], Ast::Optional(Box::new(node)))),
+        _ => Ok((rest, node)),
+    }
+}
+
+fn concat<'a>(input: &'a str, groups: &mut usize) -> ParseResult<'a, Ast> {
+    let mut nodes = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() && !rest.starts_with('|') && !rest.starts_with(')') {
+        let (next, node) = repeat(rest, groups)?;
+        nodes.push(node);
+        rest = next;
+    }
+    match nodes.len() {
+        0 => Err(input),
+        1 => Ok((rest, nodes.into_iter().next().unwrap())),
+        _ => Ok((rest, Ast::Concat(nodes))),
+    }
+}
+
+fn alternation<'a>(input: &'a str, groups: &mut usize) -> ParseResult<'a, Ast> {
+    let (mut rest, mut node) = concat(input, groups)?;
+    while let Some(next) = rest.strip_prefix('|') {
+        let (next_rest, rhs) = concat(next, groups)?;
+        node = Ast::Alternation(Box::new(node), Box::new(rhs));
+        rest = next_rest;
+    }
+    Ok((rest, node))
+}
+
+fn parse(pattern: &str) -> Result<Ast, &str> {
+    let mut groups = 0;
+    let (rest, ast) = alternation(pattern, &mut groups)?;
+    if rest.is_empty() {
+        Ok(ast)
+    } else {
+        Err(rest)
+    }
+}
+
+// Builds up an `NFAEngine` fragment by fragment, handing out a fresh,
+// never-before-used state name on each call so fragments can be wired
+// together without name collisions in the engine's `HashSet<State>`.
+struct Builder {
+    engine: NFAEngine,
+    counter: usize,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            engine: NFAEngine::new("s0"),
+            counter: 1,
+        }
+    }
+
+    fn fresh_state(&mut self) -> String {
+        let name = format!("s{}", self.counter);
+        self.counter += 1;
+        self.engine.add_state(&name);
+        name
+    }
+
+    // Returns the (start, accept) pair of the fragment built for `ast`.
+    fn fragment(&mut self, ast: &Ast) -> (String, String) {
+        match ast {
+            Ast::Literal(c) => {
+                let start = self.fresh_state();
+                let accept = self.fresh_state();
+                self.engine
+                    .add_transition(&start, &accept, Matchers::new_char(*c));
+                (start, accept)
+            }
+            Ast::Class(predicate) => {
+                let start = self.fresh_state();
+                let accept = self.fresh_state();
+                self.engine
+                    .add_transition(&start, &accept, Matchers::Character(predicate.clone()));
+                (start, accept)
+            }
+            Ast::Anchor(matcher) => {
+                let start = self.fresh_state();
+                let accept = self.fresh_state();
+                self.engine.add_transition(&start, &accept, matcher.clone());
+                (start, accept)
+            }
+            Ast::Group(id, inner) => {
+                let (start, accept) = self.fragment(inner);
+                let group_id = id.to_string();
+                self.engine.tag_group_start(&start, &group_id);
+                self.engine.tag_group_end(&accept, &group_id);
+                (start, accept)
+            }
+            Ast::Concat(nodes) => {
+                let mut nodes = nodes.iter();
+                let first = nodes.next().expect("concat always has at least one node");
+                let (start, mut accept) = self.fragment(first);
+                for node in nodes {
+                    let (next_start, next_accept) = self.fragment(node);
+                    self.engine
+                        .add_transition(&accept, &next_start, Matchers::new_epsilon());
+                    accept = next_accept;
+                }
+                (start, accept)
+            }
+            Ast::Alternation(lhs, rhs) => {
+                let (lhs_start, lhs_accept) = self.fragment(lhs);
+                let (rhs_start, rhs_accept) = self.fragment(rhs);
+                let start = self.fresh_state();
+                let accept = self.fresh_state();
+                self.engine
+                    .add_transition(&start, &lhs_start, Matchers::new_epsilon());
+                self.engine
+                    .add_transition(&start, &rhs_start, Matchers::new_epsilon());
+                self.engine
+                    .add_transition(&lhs_accept, &accept, Matchers::new_epsilon());
+                self.engine
+                    .add_transition(&rhs_accept, &accept, Matchers::new_epsilon());
+                (start, accept)
+            }
+            Ast::Star(inner) => {
+                let (inner_start, inner_accept) = self.fragment(inner);
+                let start = self.fresh_state();
+                let accept = self.fresh_state();
+                self.engine
+                    .add_transition(&start, &inner_start, Matchers::new_epsilon());
+                self.engine
+                    .add_transition(&start, &accept, Matchers::new_epsilon());
+                self.engine
+                    .add_transition(&inner_accept, &inner_start, Matchers::new_epsilon());
+                self.engine
+                    .add_transition(&inner_accept, &accept, Matchers::new_epsilon());
+                (start, accept)
+            }
+            Ast::Plus(inner) => {
+                let (inner_start, inner_accept) = self.fragment(inner);
+                let accept = self.fresh_state();
+                self.engine
+                    .add_transition(&inner_accept, &inner_start, Matchers::new_epsilon());
+                self.engine
+                    .add_transition(&inner_accept, &accept, Matchers::new_epsilon());
+                (inner_start, accept)
+            }
+            Ast::Optional(inner) => {
+                let (inner_start, inner_accept) = self.fragment(inner);
+                let start = self.fresh_state();
+                self.engine
+                    .add_transition(&start, &inner_start, Matchers::new_epsilon());
+                self.engine
+                    .add_transition(&start, &inner_accept, Matchers::new_epsilon());
+                (start, inner_accept)
+            }
+        }
+    }
+}
+
+/// Parses `pattern` and compiles it into an `NFAEngine` ready for `compute`.
+pub(crate) fn compile(pattern: &str) -> Result<NFAEngine, String> {
+    let ast = parse(pattern).map_err(|rest| format!("failed to parse pattern near '{}'", rest))?;
+    let mut builder = Builder::new();
+    let (start, accept) = builder.fragment(&ast);
+    let mut engine = builder.engine;
+    engine.set_initial_state(&start);
+    engine.set_ending_states(&[accept.as_str()]);
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compile;
+
+    #[test]
+    fn compiles_single_literal() {
+        let engine = compile("a").expect("pattern should compile");
+        assert!(engine.matches("a"));
+        assert!(engine.matches("ab"));
+        assert!(!engine.matches("b"));
+    }
+
+    #[test]
+    fn compiles_concatenation() {
+        let engine = compile("ab").expect("pattern should compile");
+        assert!(engine.matches("ab"));
+        assert!(!engine.matches("a"));
+        assert!(!engine.matches("ba"));
+    }
+
+    #[test]
+    fn compiles_alternation() {
+        let engine = compile("a|b").expect("pattern should compile");
+        assert!(engine.matches("a"));
+        assert!(engine.matches("b"));
+        assert!(!engine.matches("c"));
+    }
+
+    #[test]
+    fn compiles_kleene_star() {
+        let engine = compile("a(b|c)*d").expect("pattern should compile");
+        assert!(engine.matches("ad"));
+        assert!(engine.matches("abd"));
+        assert!(engine.matches("acd"));
+        assert!(engine.matches("abcbcd"));
+        assert!(!engine.matches("ae"));
+    }
+
+    #[test]
+    fn compiles_plus_and_optional() {
+        let plus = compile("a+").expect("pattern should compile");
+        assert!(plus.matches("a"));
+        assert!(plus.matches("aaa"));
+        assert!(!plus.matches("b"));
+
+        let optional = compile("ab?c").expect("pattern should compile");
+        assert!(optional.matches("ac"));
+        assert!(optional.matches("abc"));
+    }
+
+    #[test]
+    fn rejects_unbalanced_group() {
+        assert!(compile("(ab").is_err());
+    }
+
+    #[test]
+    fn compiles_bracket_class() {
+        let engine = compile("[abc]").expect("pattern should compile");
+        assert!(engine.matches("a"));
+        assert!(engine.matches("b"));
+        assert!(!engine.matches("d"));
+    }
+
+    #[test]
+    fn compiles_bracket_range() {
+        let engine = compile("[a-z]+").expect("pattern should compile");
+        assert!(engine.matches("hello"));
+        assert!(!engine.matches("HELLO"));
+    }
+
+    #[test]
+    fn compiles_negated_bracket_class() {
+        let engine = compile("[^0-9]").expect("pattern should compile");
+        assert!(engine.matches("a"));
+        assert!(!engine.matches("5"));
+    }
+
+    #[test]
+    fn captures_single_group() {
+        let engine = compile("a(bc)d").expect("pattern should compile");
+        assert_eq!(engine.compute("abcd"), Some(vec![(1, 3)]));
+    }
+
+    #[test]
+    fn captures_groups_in_open_paren_order() {
+        let engine = compile("(a)(b)").expect("pattern should compile");
+        assert_eq!(engine.compute("ab"), Some(vec![(0, 1), (1, 2)]));
+    }
+
+    #[test]
+    fn compiles_start_anchor() {
+        let engine = compile("^abc").expect("pattern should compile");
+        assert!(engine.matches("abc"));
+        assert!(engine.matches("abcdef"));
+        assert!(!engine.matches("xabc"));
+    }
+
+    #[test]
+    fn compiles_end_anchor() {
+        let engine = compile("abc$").expect("pattern should compile");
+        assert!(engine.matches("abc"));
+        assert!(!engine.matches("abcd"));
+    }
+
+    #[test]
+    fn captures_repeated_group() {
+        let engine = compile("(a)+").expect("pattern should compile");
+        // The engine keeps whichever capture of the repeated group it last opened - here
+        // the last iteration's span, since each later close replaces the earlier span in
+        // `captures`.
+        assert_eq!(engine.compute("aaa"), Some(vec![(2, 3)]));
+    }
+}
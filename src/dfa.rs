@@ -33,14 +33,25 @@ impl std::hash::Hash for State {
 }
 
 // We use  Rc instead of Box, since the predicate can be safely shared so we just have to do a
-// reference count
-type PredicateFn = Rc<dyn Fn(char) -> bool>;
+// reference count. The predicate runs over a whole grapheme cluster (a user-perceived
+// character, e.g. "e" or "e\u{301}") rather than a single `char`, since that's the unit
+// `compute` now matches a step against - see `graphemes` below.
+pub(crate) type PredicateFn = Rc<dyn Fn(&str) -> bool>;
+
+// Where an `Anchor` matcher requires the search to be, relative to the whole input - neither
+// consumes a grapheme cluster.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum AnchorKind {
+    Start,
+    End,
+}
 
 // We can clone matcher because it is safe to share PredicateFn since it doesn't modify anything
 #[derive(Clone)]
-enum Matchers {
+pub(crate) enum Matchers {
     Character(PredicateFn),
     Epsilon,
+    Anchor(AnchorKind),
 }
 
 impl Eq for Matchers {}
@@ -54,51 +65,125 @@ impl PartialEq for Matchers {
 impl std::hash::Hash for Matchers {
     fn hash<H: std::hash::Hasher>(&self, matcher: &mut H) {
         match self {
-            // We use unique identifiers here 0, 1 for the predicates since we can't hash otherwise
+            // We use unique identifiers here 0, 1, 2 for the predicates since we can't hash
+            // otherwise
             Matchers::Character(_) => 1.hash(matcher),
             Matchers::Epsilon => 0.hash(matcher),
+            Matchers::Anchor(kind) => {
+                2.hash(matcher);
+                kind.hash(matcher);
+            }
         }
     }
 }
 
 impl Matchers {
-    fn new_char(c: char) -> Matchers {
-        Self::Character(Rc::new(move |other: char| c == other))
+    pub(crate) fn new_char(c: char) -> Matchers {
+        let mut buf = [0u8; 4];
+        let literal = c.encode_utf8(&mut buf).to_string();
+        Self::Character(Rc::new(move |cluster: &str| cluster == literal))
     }
 
-    fn new_epsilon() -> Matchers {
+    pub(crate) fn new_epsilon() -> Matchers {
         Self::Epsilon
     }
 
-    fn matches(&self, input: &str, pos: usize) -> bool {
-        if self.is_epsilon() {
-            return true;
-        }
+    pub(crate) fn new_start_anchor() -> Matchers {
+        Self::Anchor(AnchorKind::Start)
+    }
 
-        let predicate = match self {
-            Self::Character(p) => p,
-            Self::Epsilon => return true,
-        };
+    pub(crate) fn new_end_anchor() -> Matchers {
+        Self::Anchor(AnchorKind::End)
+    }
 
-        // if we don't have a character at this postion then just return false
-        let c = if let Some(ch) = input.chars().nth(pos) {
-            ch
-        } else {
-            return false;
-        };
-        predicate(c)
+    // `clusters` is the input pre-split into grapheme clusters by `graphemes`, and `pos`
+    // indexes straight into it - an O(1) lookup, unlike the `input.chars().nth(pos)` this used
+    // to do, which re-walked the string from the start on every single transition check.
+    fn matches(&self, clusters: &[&str], pos: usize) -> bool {
+        match self {
+            Self::Epsilon => true,
+            Self::Anchor(AnchorKind::Start) => pos == 0,
+            Self::Anchor(AnchorKind::End) => pos == clusters.len(),
+            // if we don't have a cluster at this position then just return false
+            Self::Character(predicate) => match clusters.get(pos) {
+                Some(cluster) => predicate(cluster),
+                None => false,
+            },
+        }
     }
 
-    fn is_epsilon(&self) -> bool {
-        matches!(self, Self::Epsilon)
+    // Whether this matcher leaves `pos` where it was when taken - `true` for `Epsilon` and
+    // `Anchor`, which consume no grapheme cluster.
+    fn is_zero_width(&self) -> bool {
+        matches!(self, Self::Epsilon | Self::Anchor(_))
     }
+}
 
-    fn name(&self) -> &str {
-        match self {
-            Self::Character(_) => "Character",
-            Self::Epsilon => "Epsilon",
+// Unicode combining marks (general categories Mn/Mc/Me) that attach to the previous base
+// character instead of starting a new grapheme cluster. Covers the common combining-diacritic
+// blocks; not a full Unicode property lookup, but enough to keep e.g. "e" + U+0301 (combining
+// acute accent) together as one cluster.
+fn is_combining_mark(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036F   // Combining Diacritical Marks
+        | 0x1AB0..=0x1AFF // Combining Diacritical Marks Extended
+        | 0x1DC0..=0x1DFF // Combining Diacritical Marks Supplement
+        | 0x20D0..=0x20FF // Combining Diacritical Marks for Symbols
+        | 0xFE20..=0xFE2F // Combining Half Marks
+    )
+}
+
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(c as u32, 0x1F3FB..=0x1F3FF)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1F1E6..=0x1F1FF)
+}
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+
+// Splits `input` into grapheme clusters - user-perceived characters - rather than Unicode
+// scalar values, so e.g. a letter with a combining accent, or a flag made of two
+// regional-indicator symbols, is one matchable position instead of being split across two.
+// This is a best-effort approximation of Unicode's grapheme-break rules (combining marks,
+// emoji modifiers, ZWJ sequences, regional-indicator pairs), not a full implementation.
+fn graphemes(input: &str) -> Vec<&str> {
+    if input.is_ascii() {
+        // Fast path: every byte of an ASCII string is already its own complete grapheme
+        // cluster, so there's no segmentation work to do.
+        return (0..input.len()).map(|i| &input[i..i + 1]).collect();
+    }
+
+    let mut clusters = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        let mut paired_regional = false;
+        loop {
+            match chars.peek().copied() {
+                Some((_, next)) if is_combining_mark(next) || is_emoji_modifier(next) => {
+                    end += next.len_utf8();
+                    chars.next();
+                }
+                Some((_, next)) if next == ZERO_WIDTH_JOINER => {
+                    end += next.len_utf8();
+                    chars.next();
+                    if let Some((_, joined)) = chars.next() {
+                        end += joined.len_utf8();
+                    }
+                }
+                Some((_, next)) if !paired_regional && is_regional_indicator(c) && is_regional_indicator(next) => {
+                    end += next.len_utf8();
+                    chars.next();
+                    paired_regional = true;
+                }
+                _ => break,
+            }
         }
+        clusters.push(&input[start..end]);
     }
+    clusters
 }
 
 #[derive(Eq, PartialEq, Hash, Clone)]
@@ -122,12 +207,12 @@ impl State {
     }
 
     // add_transition adds the transition to the end of the list of transitions
-    pub fn add_transition(&mut self, to_state: State, matcher: Matchers) {
+    pub(crate) fn add_transition(&mut self, to_state: State, matcher: Matchers) {
         self.transitions.push(Transition { to_state, matcher })
     }
 
     // unshift_transition puts the transition at the front meaning it's the highest priority
-    pub fn unshift_transition(&mut self, to_state: State, matcher: Matchers) {
+    pub(crate) fn unshift_transition(&mut self, to_state: State, matcher: Matchers) {
         self.transitions.insert(0, Transition { to_state, matcher })
     }
 }
@@ -135,23 +220,31 @@ impl State {
 struct EngineState {
     pos: usize,
     state: String,
-    memory: Vec<String>,
+    // States already reached via a zero-width (epsilon or anchor) transition since the last
+    // character was consumed - reset to empty on every consuming step, see `compute` below.
+    visited: Vec<String>,
+    // Group ids whose `start_groups` state we've entered but whose `end_groups` state we
+    // haven't reached yet, along with the position they were opened at.
+    open_groups: Vec<(String, usize)>,
+    // Finished captures: (group id, start, end) as grapheme-cluster indices into the input;
+    // `compute` translates them to byte offsets when it returns.
+    captures: Vec<(String, usize, usize)>,
 }
 
-struct NFAEngine {
+pub(crate) struct NFAEngine {
     states: HashSet<State>,
     initial_state: String,
     ending_states: Vec<String>,
 }
 
 impl NFAEngine {
-    fn new_with_states(initial: &str, states: &[&str]) -> NFAEngine {
+    pub(crate) fn new_with_states(initial: &str, states: &[&str]) -> NFAEngine {
         let mut engine = NFAEngine::new(initial);
         engine.declare_states_with_names(states);
         engine
     }
 
-    fn new(initial: &str) -> NFAEngine {
+    pub(crate) fn new(initial: &str) -> NFAEngine {
         let mut states = HashSet::new();
         states.insert(State::new(initial));
         NFAEngine {
@@ -165,7 +258,7 @@ impl NFAEngine {
         self.states.len()
     }
 
-    fn has_state(&self, state: &str) -> bool {
+    pub(crate) fn has_state(&self, state: &str) -> bool {
         self.states.contains(&State::new(state))
     }
 
@@ -173,7 +266,7 @@ impl NFAEngine {
         self.states.get(&State::new(name))
     }
 
-    fn add_state(&mut self, state: &str) -> bool {
+    pub(crate) fn add_state(&mut self, state: &str) -> bool {
         self.states.insert(State::new(state))
     }
 
@@ -189,7 +282,7 @@ impl NFAEngine {
         }
     }
 
-    fn set_initial_state(&mut self, state: &str) {
+    pub(crate) fn set_initial_state(&mut self, state: &str) {
         if self.has_state(state) {
             self.initial_state = state.to_string()
         } else {
@@ -197,7 +290,7 @@ impl NFAEngine {
         }
     }
 
-    fn set_ending_states(&mut self, states: &[&str]) {
+    pub(crate) fn set_ending_states(&mut self, states: &[&str]) {
         states.iter().for_each(|s| {
             if !self.has_state(s) {
                 self.add_state(s);
@@ -213,12 +306,10 @@ impl NFAEngine {
         self.ending_states.contains(&state.to_string())
     }
 
-    fn add_transition(&mut self, from: &str, to: &str, matcher: Matchers) {
+    pub(crate) fn add_transition(&mut self, from: &str, to: &str, matcher: Matchers) {
         match self.states.take(&State::new(from)) {
             Some(mut s) => {
-                print!("transition<{}, {}>:{} ", s.name, to, matcher.name());
                 s.add_transition(State::new(to), matcher);
-                println!("count: {}", s.transitions.len());
                 self.states.insert(s);
             }
             None => panic!("'{}' state not found!", from),
@@ -232,19 +323,96 @@ impl NFAEngine {
         }
     }
 
-    fn compute(&self, input: &str) -> bool {
+    // Tags `state` as the entry point of capturing group `group_id`, so `compute` opens a
+    // capture for it whenever the search arrives there.
+    pub(crate) fn tag_group_start(&mut self, state: &str, group_id: &str) {
+        if let Some(mut s) = self.states.take(&State::new(state)) {
+            s.start_groups.push(group_id.to_string());
+            self.states.insert(s);
+        }
+    }
+
+    // Tags `state` as the exit point of capturing group `group_id`, so `compute` closes the
+    // capture for it whenever the search arrives there.
+    pub(crate) fn tag_group_end(&mut self, state: &str, group_id: &str) {
+        if let Some(mut s) = self.states.take(&State::new(state)) {
+            s.end_groups.push(group_id.to_string());
+            self.states.insert(s);
+        }
+    }
+
+    // Opens/closes captures for `state`'s `start_groups`/`end_groups` as the search arrives
+    // there at `pos`, mutating `open_groups`/`captures` in place.
+    fn apply_group_tags(
+        &self,
+        state: &str,
+        pos: usize,
+        open_groups: &mut Vec<(String, usize)>,
+        captures: &mut Vec<(String, usize, usize)>,
+    ) {
+        let Some(target) = self.get_state(state) else {
+            return;
+        };
+        for group in &target.start_groups {
+            open_groups.push((group.clone(), pos));
+        }
+        for group in &target.end_groups {
+            if let Some(idx) = open_groups.iter().position(|(g, _)| g == group) {
+                let (_, start) = open_groups.remove(idx);
+                // A repeated group (e.g. `(a)+`) closes more than once; keep only its last
+                // iteration's span, matching how capture groups behave elsewhere.
+                captures.retain(|(g, _, _)| g != group);
+                captures.push((group.clone(), start, pos));
+            }
+        }
+    }
+
+    // Runs the backtracking search and, on a match, returns the byte span of each group that
+    // captured, sorted by ascending group index; a group that took no part in the match has
+    // no entry. Returns `None` when there's no match.
+    pub(crate) fn compute(&self, input: &str) -> Option<Vec<(usize, usize)>> {
+        // Pre-split the input into grapheme clusters once, up front, so every transition check
+        // below is an O(1) slice index instead of re-walking the string from the start - that
+        // re-walk (`input.chars().nth(pos)`) is what used to make `compute` quadratic on long
+        // lines.
+        let clusters = graphemes(input);
+
+        // Byte offset of the start of each cluster, plus a trailing sentinel for the end of
+        // the input, so a cluster-index capture span can be translated back to the byte
+        // offsets callers expect.
+        let mut boundaries = Vec::with_capacity(clusters.len() + 1);
+        let mut offset = 0;
+        for cluster in &clusters {
+            boundaries.push(offset);
+            offset += cluster.len();
+        }
+        boundaries.push(offset);
+
         let mut stack = Vec::new();
 
+        let mut open_groups = Vec::new();
+        let mut captures = Vec::new();
+        self.apply_group_tags(&self.initial_state, 0, &mut open_groups, &mut captures);
+
         // Initial state
         stack.push(EngineState {
             pos: 0,
             state: self.initial_state.clone(),
-            memory: Vec::new(),
+            visited: Vec::new(),
+            open_groups,
+            captures,
         });
 
         while let Some(current) = stack.pop() {
             if self.is_ending_state(&current.state) {
-                return true;
+                let mut captures = current.captures;
+                captures.sort_by_key(|(group, _, _)| group.parse::<usize>().unwrap_or(usize::MAX));
+                return Some(
+                    captures
+                        .into_iter()
+                        .map(|(_, start, end)| (boundaries[start], boundaries[end]))
+                        .collect(),
+                );
             }
 
             let transitions: &[Transition] = match self.get_state(&current.state) {
@@ -254,34 +422,56 @@ impl NFAEngine {
 
             for idx in (0..transitions.len()).rev() {
                 let t = &transitions[idx];
-                if t.matcher.matches(input, current.pos) {
-                    let copy_memory = if t.matcher.is_epsilon() {
-                        // if we've been here before we continue the loop otherwise we'll get stuck
-                        if current.memory.contains(&t.matcher.name().to_string()) {
+                if t.matcher.matches(&clusters, current.pos) {
+                    let visited = if t.matcher.is_zero_width() {
+                        // We're tracking which states we've already reached via a zero-width
+                        // (epsilon or anchor) transition since the last character was
+                        // consumed, so a cycle of them (e.g. a `*` looping back on itself)
+                        // can't get us stuck, while still allowing a chain of *different*
+                        // zero-width states (e.g. star-of-alternation) to be followed all the
+                        // way through.
+                        if current.visited.contains(&t.to_state.name) {
                             continue;
                         }
-                        // we haven't been here, so lets remember it
-                        let mut copy = current.memory.clone();
-                        copy.push(t.matcher.name().to_string());
+                        let mut copy = current.visited.clone();
+                        copy.push(t.to_state.name.clone());
                         copy
                     } else {
                         Vec::new()
                     };
-                    let next_pos = if t.matcher.is_epsilon() {
+                    let next_pos = if t.matcher.is_zero_width() {
                         current.pos
                     } else {
                         current.pos + 1
                     };
+
+                    let mut open_groups = current.open_groups.clone();
+                    let mut captures = current.captures.clone();
+                    self.apply_group_tags(
+                        &t.to_state.name,
+                        next_pos,
+                        &mut open_groups,
+                        &mut captures,
+                    );
+
                     stack.push(EngineState {
                         pos: next_pos,
                         state: t.to_state.name.clone(),
-                        memory: copy_memory,
+                        visited,
+                        open_groups,
+                        captures,
                     });
                 }
             }
         }
 
-        false
+        None
+    }
+
+    // Convenience wrapper around `compute` for callers that only care whether the pattern
+    // matched, not what its capture groups spanned.
+    pub(crate) fn matches(&self, input: &str) -> bool {
+        self.compute(input).is_some()
     }
 }
 
@@ -289,7 +479,7 @@ impl NFAEngine {
 mod tests {
     use crate::dfa::NFAEngine;
 
-    use super::Matchers;
+    use super::{graphemes, Matchers};
 
     #[test]
     fn engine_construct_has_initial_state() {
@@ -345,11 +535,11 @@ mod tests {
         engine.add_transition("q1", "q2", Matchers::new_char('b'));
         engine.add_transition("q2", "q3", Matchers::new_epsilon());
 
-        assert!(engine.compute("abbbbbb"));
-        assert!(!engine.compute("aabbbbbb"));
-        assert!(engine.compute("ab"));
-        assert!(engine.compute("abc"));
-        assert!(!engine.compute("a"));
+        assert!(engine.matches("abbbbbb"));
+        assert!(!engine.matches("aabbbbbb"));
+        assert!(engine.matches("ab"));
+        assert!(engine.matches("abc"));
+        assert!(!engine.matches("a"));
     }
 
     #[test]
@@ -360,6 +550,43 @@ mod tests {
         engine.add_transition("q1", "q1", Matchers::Epsilon);
         engine.add_transition("q1", "q2", Matchers::new_char('b'));
 
-        assert!(engine.compute("ab"));
+        assert!(engine.matches("ab"));
+    }
+
+    #[test]
+    fn compute_returns_group_captures() {
+        let mut engine = NFAEngine::new_with_states("q0", &["q0", "q1", "q2", "q3"]);
+        engine.set_ending_states(&["q3"]);
+        engine.tag_group_start("q1", "1");
+        engine.add_transition("q0", "q1", Matchers::new_epsilon());
+        engine.tag_group_end("q2", "1");
+        engine.add_transition("q1", "q2", Matchers::new_char('a'));
+        engine.add_transition("q2", "q3", Matchers::new_epsilon());
+
+        assert_eq!(engine.compute("a"), Some(vec![(0, 1)]));
+        assert_eq!(engine.compute("b"), None);
+    }
+
+    #[test]
+    fn graphemes_keeps_combining_marks_with_their_base_char() {
+        assert_eq!(graphemes("cafe\u{301}"), vec!["c", "a", "f", "e\u{301}"]);
+    }
+
+    #[test]
+    fn graphemes_pairs_regional_indicators_into_one_flag_cluster() {
+        // "🇿🇦" (South Africa) is the two regional-indicator symbols U+1F1FF U+1F1E6.
+        assert_eq!(graphemes("\u{1F1FF}\u{1F1E6}"), vec!["\u{1F1FF}\u{1F1E6}"]);
+    }
+
+    #[test]
+    fn compute_treats_base_plus_combining_accent_as_one_position() {
+        let mut engine = NFAEngine::new_with_states("q0", &["q0", "q1"]);
+        engine.set_ending_states(&["q1"]);
+        engine.add_transition("q0", "q1", Matchers::new_char('e'));
+
+        // "e" + a combining acute accent is a single grapheme cluster, so a literal `e`
+        // transition - which only matches a cluster that is exactly "e" - must not match it.
+        assert!(!engine.matches("e\u{301}"));
+        assert!(engine.matches("e"));
     }
 }
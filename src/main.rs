@@ -1,110 +1,396 @@
 use std::env;
-use std::io;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::process;
 
 pub mod dfa;
+mod compiler;
+mod parser;
 
-enum Pattern {
-    Digit(String),
-    Literal(String),
-    AlphaNumeric(String),
+use dfa::NFAEngine;
+use parser::stream::{self, StreamResult};
+
+struct Options {
+    pattern: String,
+    show_line_numbers: bool,
+    recursive: bool,
+    paths: Vec<String>,
 }
 
-impl Pattern {
-    fn from(pattern: &str) -> Pattern {
-        match pattern {
-            "\\d" => Pattern::Digit(pattern.to_string()),
-            "\\w" => Pattern::AlphaNumeric(pattern.to_string()),
-            _ => Pattern::Literal(pattern.to_string()),
+impl Options {
+    // Parses everything after the program name: `-E <pattern>` (required), plus the optional
+    // `-n` (line numbers) and `-r` (recurse into directories) flags, in any order, with
+    // anything left over treated as a file path.
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut pattern = None;
+        let mut show_line_numbers = false;
+        let mut recursive = false;
+        let mut paths = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-E" => {
+                    pattern = Some(
+                        args.next()
+                            .ok_or("expected a pattern after '-E'")?
+                            .clone(),
+                    );
+                }
+                "-n" => show_line_numbers = true,
+                "-r" => recursive = true,
+                other => paths.push(other.to_string()),
+            }
         }
+
+        Ok(Options {
+            pattern: pattern.ok_or("expected a pattern given with '-E'")?,
+            show_line_numbers,
+            recursive,
+            paths,
+        })
     }
-    fn match_on(&self, line: &str) -> Result<bool, String> {
-        match self {
-            Pattern::Digit(pattern) => Ok(handle_digit(line, pattern)),
-            Pattern::AlphaNumeric(pattern) => Ok(handle_alpha_numeric(line, pattern)),
-            Pattern::Literal(pattern) => {
-                if pattern.chars().count() == 1 {
-                    Ok(line.contains(pattern))
-                } else {
-                    Err(format!("unknown literal pattern: {}", pattern))
-                }
+}
+
+// Resolves `paths` to the concrete files to search: a path is searched as-is, or - when
+// `recursive` is set - walked for every regular file beneath it if it's a directory. A
+// directory given without `-r` is skipped, matching grep's default behaviour.
+fn collect_files(paths: &[String], recursive: bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = PathBuf::from(path);
+        if path.is_dir() {
+            if recursive {
+                walk_dir(&path, &mut files)?;
             }
+        } else {
+            files.push(path);
         }
     }
+    Ok(files)
 }
 
-fn handle_digit(input_line: &str, _pattern: &str) -> bool {
-    input_line.chars().filter(|c| c.is_digit(10)).count() > 0
+fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk_dir(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
 }
 
-fn handle_alpha_numeric(input_line: &str, _pattern: &str) -> bool {
-    input_line.chars().filter(|c| c.is_alphanumeric()).count() > 0
+// Does `pattern` match anywhere in `line`? The NFA only ever matches starting at position 0 of
+// whatever it's given (see `NFAEngine::compute`), so finding a match anywhere in the line means
+// retrying from every offset - except for a `^`-anchored pattern, which must only ever be tried
+// at the true start of the line; retrying it from a later offset would let it match wherever we
+// happened to resume counting from, not just the line's real start.
+fn line_matches(engine: &NFAEngine, pattern: &str, line: &str) -> bool {
+    if pattern.starts_with('^') {
+        return engine.matches(line);
+    }
+    (0..=line.len())
+        .filter(|&start| line.is_char_boundary(start))
+        .any(|start| engine.matches(&line[start..]))
 }
 
-fn match_pattern(input_line: &str, pattern: &str) -> bool {
-    Pattern::from(pattern)
-        .match_on(input_line)
-        .expect("Pattern match failure")
+// A streaming parser that accumulates every char up to (but not including) the next '\n' -
+// i.e. one line, fed through `parser::stream` so it can be built up across several reads. The
+// line is only examined once its '\n' arrives.
+fn line_stream_parser() -> stream::StreamParser<Vec<char>> {
+    stream::zero_or_more_stream(stream::filter_stream(stream::char_parser_stream(), |c: &char| {
+        *c != '\n'
+    }))
 }
 
-// Usage: echo <input_text> | your_grep.sh -E <pattern>
-fn main() {
-    // You can use print statements as follows for debugging, they'll be visible when running tests.
-    println!("Logs from your program will appear here!");
+// Drains every line already fully buffered in `input` into `lines`, re-parsing the remainder
+// from scratch each time a '\n' is found - a single read can hand us more than one line at once.
+// Returns the `Partial` left waiting on whatever's after the last '\n', plus whether that
+// leftover is a still-open, unterminated line (as opposed to a clean stop right at a boundary).
+fn split_complete_lines(input: &str, lines: &mut Vec<String>) -> (stream::Partial<Vec<char>>, bool) {
+    let mut input = input.to_string();
+    loop {
+        match stream::init(&line_stream_parser(), &input) {
+            StreamResult::Done(rest, chars) => {
+                lines.push(chars.into_iter().collect());
+                input = rest.strip_prefix('\n').unwrap_or(&rest).to_string();
+            }
+            StreamResult::Continue(partial) => return (partial, !input.is_empty()),
+        }
+    }
+}
 
-    if env::args().nth(1).unwrap() != "-E" {
-        println!("Expected first argument to be '-E'");
-        process::exit(1);
+// Feeds `chunk` into `partial`, appending every line it completes to `lines`, and resumes
+// splitting any further lines already sitting in what's left over.
+fn advance(
+    partial: stream::Partial<Vec<char>>,
+    chunk: &str,
+    lines: &mut Vec<String>,
+) -> (stream::Partial<Vec<char>>, bool) {
+    match partial.feed(chunk) {
+        StreamResult::Done(rest, chars) => {
+            lines.push(chars.into_iter().collect());
+            let remainder = rest.strip_prefix('\n').unwrap_or(&rest);
+            split_complete_lines(remainder, lines)
+        }
+        StreamResult::Continue(next) => (next, !chunk.is_empty()),
+    }
+}
+
+fn report_matches(
+    lines: &mut Vec<String>,
+    line_no: &mut usize,
+    engine: &NFAEngine,
+    pattern: &str,
+    label: Option<&str>,
+    show_line_numbers: bool,
+    matched_any: &mut bool,
+) {
+    for line in lines.drain(..) {
+        *line_no += 1;
+        if line_matches(engine, pattern, &line) {
+            *matched_any = true;
+            let mut prefix = String::new();
+            if let Some(label) = label {
+                prefix.push_str(label);
+                prefix.push(':');
+            }
+            if show_line_numbers {
+                prefix.push_str(&line_no.to_string());
+                prefix.push(':');
+            }
+            println!("{}{}", prefix, line);
+        }
     }
+}
+
+// Searches `reader` for `pattern`, printing each match (prefixed with `label` and/or its
+// 1-based line number when requested). Returns whether at least one line matched.
+//
+// Lines are assembled with `parser::stream`'s `Partial`/`feed` instead of `BufRead::lines()`,
+// so a line split across several reads is assembled piece by piece, and a match is reported
+// as soon as that line's '\n' arrives; a line is never examined before it is complete.
+// Each raw chunk is decoded with `from_utf8_lossy`, so (as with the grapheme segmentation in
+// `dfa.rs`) a multi-byte character split exactly across a chunk boundary may get mangled - a
+// deliberate, documented imprecision rather than a promise of full correctness.
+fn search<R: Read>(
+    mut reader: R,
+    engine: &NFAEngine,
+    pattern: &str,
+    label: Option<&str>,
+    show_line_numbers: bool,
+) -> io::Result<bool> {
+    let mut matched_any = false;
+    let mut line_no = 0usize;
+    let mut lines = Vec::new();
+    let (mut partial, mut has_pending) = split_complete_lines("", &mut lines);
+    report_matches(
+        &mut lines,
+        &mut line_no,
+        engine,
+        pattern,
+        label,
+        show_line_numbers,
+        &mut matched_any,
+    );
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+        let (next_partial, pending) = advance(partial, &chunk, &mut lines);
+        partial = next_partial;
+        has_pending = pending;
+        report_matches(
+            &mut lines,
+            &mut line_no,
+            engine,
+            pattern,
+            label,
+            show_line_numbers,
+            &mut matched_any,
+        );
+    }
+
+    if has_pending {
+        let (_, _) = advance(partial, "\n", &mut lines);
+        report_matches(
+            &mut lines,
+            &mut line_no,
+            engine,
+            pattern,
+            label,
+            show_line_numbers,
+            &mut matched_any,
+        );
+    }
+
+    Ok(matched_any)
+}
+
+// Usage: your_grep.sh -E <pattern> [-n] [-r] [file ...]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = Options::parse(&args).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
+
+    let engine = compiler::compile(&options.pattern).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
 
-    let pattern = env::args().nth(2).unwrap();
-    let mut input_line = String::new();
+    let files = collect_files(&options.paths, options.recursive).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
 
-    io::stdin().read_line(&mut input_line).unwrap();
+    // Only label matches with their file name once there's more than one file to tell them
+    // apart, same as grep.
+    let show_labels = files.len() > 1;
+    let mut matched_any = false;
 
-    // Uncomment this block to pass the first stage
-    if match_pattern(&input_line, &pattern) {
-        process::exit(0)
+    if files.is_empty() {
+        let stdin = io::stdin();
+        match search(
+            stdin.lock(),
+            &engine,
+            &options.pattern,
+            None,
+            options.show_line_numbers,
+        ) {
+            Ok(matched) => matched_any = matched,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(2);
+            }
+        }
     } else {
-        process::exit(1)
+        for path in &files {
+            let file = match fs::File::open(path) {
+                Ok(file) => file,
+                Err(err) => {
+                    eprintln!("{}: {}", path.display(), err);
+                    continue;
+                }
+            };
+            let label = show_labels.then(|| path.display().to_string());
+            match search(
+                io::BufReader::new(file),
+                &engine,
+                &options.pattern,
+                label.as_deref(),
+                options.show_line_numbers,
+            ) {
+                Ok(matched) => matched_any = matched_any || matched,
+                Err(err) => eprintln!("{}: {}", path.display(), err),
+            }
+        }
     }
+
+    process::exit(if matched_any { 0 } else { 1 });
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Pattern;
+    use super::*;
+
+    #[test]
+    fn options_parse_reads_flags_and_paths() {
+        let args: Vec<String> = ["-E", "a+", "-n", "-r", "src", "tests"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let options = Options::parse(&args).expect("should parse");
+        assert_eq!(options.pattern, "a+");
+        assert!(options.show_line_numbers);
+        assert!(options.recursive);
+        assert_eq!(options.paths, vec!["src", "tests"]);
+    }
+
+    #[test]
+    fn options_parse_requires_a_pattern() {
+        let args: Vec<String> = ["-n".to_string()].to_vec();
+        assert!(Options::parse(&args).is_err());
+    }
 
     #[test]
-    fn pattern_from_returns_correct_enum() {
-        assert!(matches!(Pattern::from("\\d"), Pattern::Digit(_)));
-        assert!(matches!(Pattern::from("\\w"), Pattern::AlphaNumeric(_)));
-        assert!(matches!(Pattern::from("f"), Pattern::Literal(_)));
+    fn line_matches_finds_a_match_anywhere_in_the_line() {
+        let engine = compiler::compile("at").expect("pattern should compile");
+        assert!(line_matches(&engine, "at", "cat"));
+        assert!(!line_matches(&engine, "at", "cot"));
     }
 
     #[test]
-    fn digit_character_class() {
-        let p = Pattern::Digit("\\d".to_string());
-        assert!(matches!(p.match_on("apple123"), Ok(true)));
-        assert!(matches!(p.match_on("apple"), Ok(false)));
-        assert!(matches!(p.match_on("---"), Ok(false)));
+    fn line_matches_respects_start_anchor() {
+        let engine = compiler::compile("^at").expect("pattern should compile");
+        assert!(line_matches(&engine, "^at", "at the door"));
+        assert!(!line_matches(&engine, "^at", "cat"));
+    }
+
+    #[test]
+    fn line_matches_respects_end_anchor() {
+        let engine = compiler::compile("at$").expect("pattern should compile");
+        assert!(line_matches(&engine, "at$", "the cat"));
+        assert!(!line_matches(&engine, "at$", "attic"));
+    }
+
+    #[test]
+    fn advance_completes_a_line_only_once_its_newline_arrives() {
+        let mut lines = Vec::new();
+        let (partial, _) = split_complete_lines("", &mut lines);
+
+        let (partial, has_pending) = advance(partial, "ca", &mut lines);
+        assert!(lines.is_empty());
+        assert!(has_pending);
+
+        let (_, has_pending) = advance(partial, "t\ndog", &mut lines);
+        assert_eq!(lines, vec!["cat".to_string()]);
+        assert!(has_pending); // "dog" has no terminator yet
+    }
+
+    // A fake `Read` that hands back one fixed chunk per call, so `search` is forced to
+    // assemble a line from pieces that arrive across several reads, exactly like a slow pipe.
+    struct ChunkedReader {
+        chunks: std::collections::VecDeque<&'static [u8]>,
+    }
+
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.chunks.pop_front() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                }
+                None => Ok(0),
+            }
+        }
     }
 
     #[test]
-    fn alphanumeric_character_class() {
-        let p = Pattern::AlphaNumeric("\\w".to_string());
-        assert!(matches!(p.match_on("apple123"), Ok(true)));
-        assert!(matches!(p.match_on("apple"), Ok(true)));
-        // just punctuation should fail
-        assert!(matches!(p.match_on("---"), Ok(false)));
-        // letters, numbers and some punctuation should pass
-        assert!(matches!(p.match_on("alph4-num3ric"), Ok(true)));
+    fn search_finds_a_match_split_across_reads() {
+        let engine = compiler::compile("at").expect("pattern should compile");
+        let reader = ChunkedReader {
+            chunks: vec![b"c".as_slice(), b"at\ndog\n".as_slice()].into(),
+        };
+        let matched = search(reader, &engine, "at", None, false).expect("search should succeed");
+        assert!(matched);
     }
 
     #[test]
-    fn literal_match_on() {
-        let p = Pattern::Literal("f".to_string());
-        assert!(matches!(p.match_on("f"), Ok(true)));
-        assert!(matches!(p.match_on("a"), Ok(false)));
-        assert!(matches!(p.match_on(""), Ok(false)));
+    fn search_reports_an_unterminated_final_line() {
+        let engine = compiler::compile("og").expect("pattern should compile");
+        let reader = ChunkedReader {
+            chunks: vec![b"cat\nd".as_slice(), b"og".as_slice()].into(),
+        };
+        let matched = search(reader, &engine, "og", None, false).expect("search should succeed");
+        assert!(matched);
     }
 }
@@ -1,30 +1,48 @@
 use core::result::Result;
-use std::error::Error;
-use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug)]
-enum ParseError {
-    NoMatchFound,
-    InvalidNumber,
-}
+use crate::dfa::PredicateFn;
 
-impl fmt::Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            ParseError::InvalidNumber => write!(f, "Failed to parse number"),
-            ParseError::NoMatchFound => write!(f, "No match found"),
-        }
-    }
-}
+pub(crate) type ParseResult<'a, Output> = Result<(&'a str, Output), &'a str>;
 
-impl Error for ParseError {}
+// A fluent alternative to nesting parser calls: any `Fn(&str) -> ParseResult` closure
+// implements this (see the blanket impl further down), so sub-parsers can be built by
+// chaining methods - `char_parser().filter(is_plain_char).map(Ast::Literal)` - instead of
+// nesting one combinator call inside another.
+pub(crate) trait Parser<'a, Output> {
+    fn parse(&self, input: &'a str) -> ParseResult<'a, Output>;
 
-type ParseResult<'a, Output> = Result<(&'a str, Output), &'a str>;
+    // Fails the whole parse unless the output also satisfies `pred`.
+    fn filter<F>(self, pred: F) -> impl Fn(&'a str) -> ParseResult<'a, Output>
+    where
+        Self: Sized + 'a,
+        Output: 'a,
+        F: Fn(&Output) -> bool + 'a,
+    {
+        move |input| match self.parse(input) {
+            Ok((next, out)) if pred(&out) => Ok((next, out)),
+            Ok(_) => Err(input),
+            Err(e) => Err(e),
+        }
+    }
 
-trait Parser<'a, Output> {
-    type Output;
+    fn map<F, B>(self, map_fn: F) -> impl Fn(&'a str) -> ParseResult<'a, B>
+    where
+        Self: Sized + 'a,
+        Output: 'a,
+        F: Fn(Output) -> B + 'a,
+    {
+        move |input| self.parse(input).map(|(next, out)| (next, map_fn(out)))
+    }
+}
 
-    fn parse(&self, input: &'a str) -> ParseResult<'a, Output>;
+impl<'a, F, Output> Parser<'a, Output> for F
+where
+    F: Fn(&'a str) -> ParseResult<'a, Output>,
+{
+    fn parse(&self, input: &'a str) -> ParseResult<'a, Output> {
+        self(input)
+    }
 }
 
 pub fn char_parser() -> impl Fn(&str) -> ParseResult<char> {
@@ -37,97 +55,295 @@ pub fn char_parser() -> impl Fn(&str) -> ParseResult<char> {
     }
 }
 
-pub fn filter<P, F, O>(parser: P, pred: F) -> impl Fn(&str) -> Result<(&str, O), &str>
-where
-    P: Fn(&str) -> Result<(&str, O), &str>,
-    F: Fn(&O) -> bool,
-{
-    move |input: &str| {
-        let result = (parser)(input);
-        match result {
-            Ok((next, c)) => {
-                if pred(&c) {
-                    Ok((next, c))
-                } else {
-                    Err(input)
-                }
-            }
-            Err(e) => Err(e),
+// A single member of a bracket expression: either one literal char (`a`) or
+// an inclusive range (`a-z`).
+enum ClassMember {
+    Char(char),
+    Range(char, char),
+}
+
+impl ClassMember {
+    fn contains(&self, c: char) -> bool {
+        match self {
+            ClassMember::Char(member) => *member == c,
+            ClassMember::Range(lo, hi) => *lo <= c && c <= *hi,
         }
     }
 }
 
-pub fn map<P, F, A, B>(parser: P, map_fn: F) -> impl Fn(&str) -> Result<(&str, B), &str>
-where
-    P: Fn(&str) -> Result<(&str, A), &str>,
-    F: Fn(A) -> B,
-{
-    move |input| match parser(input) {
-        Ok((next, r)) => Ok((next, map_fn(r))),
-        Err(err) => Err(err),
+// Splits a bracket expression's body (the part between `[`/`[^` and `]`)
+// into its members, turning `a`, `-`, `z` into a single `Range('a', 'z')`
+// rather than three individual chars.
+fn class_members(body: &str) -> Vec<ClassMember> {
+    let chars: Vec<char> = body.chars().collect();
+    let mut members = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if i + 2 < chars.len() && chars[i + 1] == '-' {
+            members.push(ClassMember::Range(chars[i], chars[i + 2]));
+            i += 3;
+        } else {
+            members.push(ClassMember::Char(chars[i]));
+            i += 1;
+        }
     }
+    members
 }
 
-pub fn digit_parser() -> impl Fn(&str) -> ParseResult<String> {
-    map(
-        filter(char_parser(), |c: &char| c.is_digit(10)),
-        |c: char| c.to_string(),
-    )
+// Parses a POSIX-style bracket expression - `[abc]`, `[a-z]`, `[^0-9]` - into
+// a `PredicateFn` testing set membership (inclusive ranges, and negation when
+// the class opens with `^`). The caller hands this predicate straight to an
+// NFA builder as a `Matchers::Character`.
+pub fn class_parser() -> impl Fn(&str) -> ParseResult<PredicateFn> {
+    move |input: &str| {
+        let rest = input.strip_prefix('[').ok_or(input)?;
+        let (rest, negate) = match rest.strip_prefix('^') {
+            Some(rest) => (rest, true),
+            None => (rest, false),
+        };
+        let close = rest.find(']').ok_or(input)?;
+        let body = &rest[..close];
+        if body.is_empty() {
+            return Err(input);
+        }
+        let members = class_members(body);
+        let rest = &rest[close + 1..];
+
+        // A bracket expression matches a single character, so a multi-character grapheme
+        // cluster (e.g. a letter plus a combining accent) never matches one.
+        let predicate: PredicateFn = Rc::new(move |cluster: &str| {
+            let mut chars = cluster.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => members.iter().any(|member| member.contains(c)) != negate,
+                _ => false,
+            }
+        });
+        Ok((rest, predicate))
+    }
 }
 
-pub fn one_or_more<P, O>(parser: P) -> impl Fn(&str) -> ParseResult<Vec<O>>
-where
-    P: Fn(&str) -> ParseResult<O>,
-{
-    move |input| {
-        let mut s = input;
-        let mut matches = vec![];
+// Streaming, resumable parsing.
+//
+// `ParseResult` needs the whole input up front: if the buffer ends mid-match
+// the only option is to fail with `Err(input)`, even though the match might
+// simply continue in the next chunk. `StreamResult` adds a third outcome -
+// `Continue` - for "not enough input yet to say", so a parser fed incremental
+// chunks (e.g. stdin read in pieces) can pause at a chunk boundary and be
+// resumed with `Partial::feed` instead of aborting.
+//
+// Element parsers built on `char_parser_stream` signal "did I match" with an
+// `Option` in the `Done` payload rather than an `Err`, since `StreamResult`
+// itself has no failure variant - `zero_or_more_stream`/`one_or_more_stream`
+// stop accumulating on `Done(_, None)` and treat it as the end of the run.
+pub(crate) mod stream {
+    use std::rc::Rc;
 
-        if let Ok((next, r)) = parser(input) {
-            matches.push(r);
-            s = next;
-        } else {
-            return Err(s);
+    pub(crate) enum StreamResult<Output> {
+        // The parser is finished; the `String` is whatever input it didn't consume.
+        Done(String, Output),
+        // Not enough input to decide yet - feed more via `Partial::feed` to resume.
+        Continue(Partial<Output>),
+    }
+
+    pub(crate) type StreamParser<Output> = Rc<dyn Fn(&str) -> StreamResult<Output>>;
+
+    // A parser paused at a chunk boundary, holding whatever input it had buffered
+    // and a continuation to resume from once more input arrives.
+    pub(crate) struct Partial<Output> {
+        buffered: String,
+        resume: StreamParser<Output>,
+    }
+
+    impl<Output> Partial<Output> {
+        pub(crate) fn feed(self, chunk: &str) -> StreamResult<Output> {
+            let mut buffered = self.buffered;
+            buffered.push_str(chunk);
+            (self.resume)(&buffered)
         }
+    }
+
+    // Starts a streaming parse of `parser` over `input`.
+    pub(crate) fn init<Output>(parser: &StreamParser<Output>, input: &str) -> StreamResult<Output> {
+        (parser)(input)
+    }
+
+    pub(crate) fn char_parser_stream() -> StreamParser<char> {
+        Rc::new(|input: &str| match input.chars().next() {
+            Some(c) => StreamResult::Done(input[c.len_utf8()..].to_string(), c),
+            None => StreamResult::Continue(Partial {
+                buffered: String::new(),
+                resume: char_parser_stream(),
+            }),
+        })
+    }
 
-        while !s.is_empty() {
-            match parser(s) {
-                Ok((next, r)) => {
-                    matches.push(r);
-                    s = next;
+    pub(crate) fn filter_stream<Output, F>(
+        parser: StreamParser<Output>,
+        pred: F,
+    ) -> StreamParser<Option<Output>>
+    where
+        Output: Clone + 'static,
+        F: Fn(&Output) -> bool + Clone + 'static,
+    {
+        fn apply<Output, F>(
+            parser: StreamParser<Output>,
+            pred: F,
+            input: &str,
+        ) -> StreamResult<Option<Output>>
+        where
+            Output: Clone + 'static,
+            F: Fn(&Output) -> bool + Clone + 'static,
+        {
+            match (parser)(input) {
+                StreamResult::Done(rest, out) => {
+                    if pred(&out) {
+                        StreamResult::Done(rest, Some(out))
+                    } else {
+                        StreamResult::Done(input.to_string(), None)
+                    }
+                }
+                StreamResult::Continue(partial) => {
+                    let resume = partial.resume;
+                    let pred = pred.clone();
+                    StreamResult::Continue(Partial {
+                        buffered: partial.buffered,
+                        resume: Rc::new(move |more: &str| apply(resume.clone(), pred.clone(), more)),
+                    })
                 }
-                Err(_) => break,
             }
         }
-        Ok((s, matches))
+
+        Rc::new(move |input: &str| apply(parser.clone(), pred.clone(), input))
     }
-}
 
-pub fn zero_or_more<P>(parser: P) -> impl Fn(&str) -> ParseResult<Vec<&str>>
-where
-    P: Fn(&str) -> ParseResult<&str>,
-{
-    move |input| {
-        let mut s = input;
-        let mut matches = vec![];
-        while !s.is_empty() {
-            match parser(s) {
-                Ok((next, r)) => {
-                    matches.push(r);
-                    s = next;
+    pub(crate) fn map_stream<A, B, F>(parser: StreamParser<A>, map_fn: F) -> StreamParser<B>
+    where
+        A: Clone + 'static,
+        B: 'static,
+        F: Fn(A) -> B + Clone + 'static,
+    {
+        fn apply<A, B, F>(parser: StreamParser<A>, map_fn: F, input: &str) -> StreamResult<B>
+        where
+            A: Clone + 'static,
+            B: 'static,
+            F: Fn(A) -> B + Clone + 'static,
+        {
+            match (parser)(input) {
+                StreamResult::Done(rest, out) => StreamResult::Done(rest, map_fn(out)),
+                StreamResult::Continue(partial) => {
+                    let resume = partial.resume;
+                    let map_fn = map_fn.clone();
+                    StreamResult::Continue(Partial {
+                        buffered: partial.buffered,
+                        resume: Rc::new(move |more: &str| apply(resume.clone(), map_fn.clone(), more)),
+                    })
                 }
-                Err(_) => break,
             }
         }
-        Ok((s, matches))
+
+        Rc::new(move |input: &str| apply(parser.clone(), map_fn.clone(), input))
     }
-}
 
-pub fn number_parser() -> impl Fn(&str) -> ParseResult<String> {
-    map(one_or_more(digit_parser()), |r: Vec<String>| {
-        let s: String = r.concat();
-        s
-    })
+    // Repeatedly applies `parser` (an element parser that reports a non-match as
+    // `Done(_, None)`) until it sees a non-match or runs out of input. Shared by
+    // `zero_or_more_stream` and `one_or_more_stream` - this crate's `StreamResult`
+    // has no failure variant, so neither can reject an empty run; callers that
+    // care about the "at least one" distinction should check the returned `Vec`.
+    fn accumulate<Output>(
+        parser: StreamParser<Option<Output>>,
+        input: &str,
+        acc: Vec<Output>,
+    ) -> StreamResult<Vec<Output>>
+    where
+        Output: Clone + 'static,
+    {
+        match (parser)(input) {
+            StreamResult::Done(rest, Some(out)) => {
+                let mut acc = acc;
+                acc.push(out);
+                accumulate(parser, &rest, acc)
+            }
+            StreamResult::Done(rest, None) => StreamResult::Done(rest, acc),
+            StreamResult::Continue(partial) => {
+                let resume = partial.resume;
+                StreamResult::Continue(Partial {
+                    buffered: partial.buffered,
+                    resume: Rc::new(move |more: &str| accumulate(resume.clone(), more, acc.clone())),
+                })
+            }
+        }
+    }
+
+    pub(crate) fn zero_or_more_stream<Output>(
+        parser: StreamParser<Option<Output>>,
+    ) -> StreamParser<Vec<Output>>
+    where
+        Output: Clone + 'static,
+    {
+        Rc::new(move |input: &str| accumulate(parser.clone(), input, Vec::new()))
+    }
+
+    pub(crate) fn one_or_more_stream<Output>(
+        parser: StreamParser<Option<Output>>,
+    ) -> StreamParser<Vec<Output>>
+    where
+        Output: Clone + 'static,
+    {
+        zero_or_more_stream(parser)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{
+            char_parser_stream, filter_stream, init, map_stream, one_or_more_stream, StreamResult,
+        };
+
+        #[test]
+        fn char_parser_stream_pauses_on_empty_input() {
+            let parser = char_parser_stream();
+            match init(&parser, "") {
+                StreamResult::Continue(partial) => match partial.feed("w") {
+                    StreamResult::Done(rest, c) => {
+                        assert_eq!(c, 'w');
+                        assert_eq!(rest, "");
+                    }
+                    StreamResult::Continue(_) => panic!("expected Done after feeding a char"),
+                },
+                StreamResult::Done(..) => panic!("expected Continue on empty input"),
+            }
+        }
+
+        #[test]
+        fn map_stream_transforms_the_output() {
+            let parser = map_stream(char_parser_stream(), |c: char| c.to_ascii_uppercase());
+            match init(&parser, "w") {
+                StreamResult::Done(rest, c) => {
+                    assert_eq!(c, 'W');
+                    assert_eq!(rest, "");
+                }
+                StreamResult::Continue(_) => panic!("unexpected Continue"),
+            }
+        }
+
+        #[test]
+        fn streams_a_run_across_chunk_boundaries() {
+            let letters =
+                one_or_more_stream(filter_stream(char_parser_stream(), |c: &char| c.is_alphabetic()));
+
+            match init(&letters, "ab") {
+                StreamResult::Continue(partial) => match partial.feed("c123") {
+                    StreamResult::Done(rest, matched) => {
+                        assert_eq!(matched, vec!['a', 'b', 'c']);
+                        assert_eq!(rest, "123");
+                    }
+                    StreamResult::Continue(_) => panic!("expected Done once a digit breaks the run"),
+                },
+                StreamResult::Done(..) => {
+                    panic!("expected Continue before the chunk boundary is resolved")
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -148,26 +364,61 @@ mod tests {
     }
 
     #[test]
-    fn digit_parser() {
-        let parser = parser::digit_parser();
-        match parser("1william") {
-            Ok((rest, d)) => {
-                assert!(d == "1");
-                assert!(rest == "william")
+    fn class_parser_matches_explicit_members() {
+        let parse = parser::class_parser();
+        match parse("[abc]def") {
+            Ok((rest, predicate)) => {
+                assert!(predicate("a"));
+                assert!(predicate("b"));
+                assert!(!predicate("d"));
+                assert!(rest == "def");
             }
-            Err(e) => panic!("unexpected error! {}", e),
+            Err(_) => panic!("unexpected error!"),
         }
     }
 
     #[test]
-    fn number_parser() {
-        let parse = parser::number_parser();
-        match parse("12a3william") {
-            Ok((rest, num)) => {
-                assert!(num == "12");
-                assert!(rest == "a3william");
+    fn class_parser_matches_ranges() {
+        let parse = parser::class_parser();
+        match parse("[a-z0-9]!") {
+            Ok((rest, predicate)) => {
+                assert!(predicate("m"));
+                assert!(predicate("5"));
+                assert!(!predicate("!"));
+                assert!(rest == "!");
             }
-            Err(_) => panic!("number parser unexpected error!"),
+            Err(_) => panic!("unexpected error!"),
         }
     }
+
+    #[test]
+    fn class_parser_handles_negation() {
+        let parse = parser::class_parser();
+        match parse("[^a-z]") {
+            Ok((rest, predicate)) => {
+                assert!(!predicate("m"));
+                assert!(predicate("M"));
+                assert!(rest.is_empty());
+            }
+            Err(_) => panic!("unexpected error!"),
+        }
+    }
+
+    #[test]
+    fn class_parser_rejects_unclosed_class() {
+        let parse = parser::class_parser();
+        assert!(parse("[abc").is_err());
+    }
+
+    #[test]
+    fn fluent_filter_and_map_chain_like_compiler_rs_does() {
+        use super::Parser;
+
+        let uppercase_vowel = parser::char_parser()
+            .filter(|c: &char| "aeiou".contains(*c))
+            .map(|c: char| c.to_ascii_uppercase());
+
+        assert!(matches!(uppercase_vowel.parse("euston"), Ok((_, 'E'))));
+        assert!(uppercase_vowel.parse("london").is_err());
+    }
 }